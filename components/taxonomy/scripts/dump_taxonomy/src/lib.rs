@@ -1,4 +1,5 @@
 extern crate bson;
+extern crate chrono;
 extern crate derive_more;
 extern crate failure;
 extern crate heck;
@@ -7,6 +8,7 @@ extern crate log;
 extern crate mongodb;
 extern crate serde;
 extern crate serde_derive;
+extern crate serde_json;
 extern crate titlecase;
 extern crate toml;
 
@@ -22,9 +24,13 @@ use log::warn;
 
 use bson::{Bson, Document};
 
+use chrono::{DateTime, Utc};
+
 use serde_derive::{Deserialize, Serialize};
 
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
 use std::iter::FromIterator;
 use std::str::FromStr;
 
@@ -32,6 +38,68 @@ use titlecase::titlecase;
 
 type Result<T> = ::std::result::Result<T, Error>;
 
+/// An open, arbitrarily-typed value attached to a taxonomy entry, for
+/// per-entry attributes (numeric weights, booleans, URLs, dates, ...) that
+/// don't warrant a first-class field
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AttributeValue {
+    /// A nested mapping of attribute values
+    Map(HashMap<String, AttributeValue>),
+    /// A list of attribute values
+    List(Vec<AttributeValue>),
+    /// A boolean
+    Bool(bool),
+    /// A floating point or integer number
+    Number(f64),
+    /// A UTF-8 string
+    String(String),
+    /// An RFC3339-formatted date/time, preserving BSON's native datetime
+    /// typing across a read/write round trip instead of degrading to
+    /// `String`
+    Date(String),
+    /// The absence of a value
+    Null,
+}
+
+/// The BSON field names `CollectionEntry` already models as first-class
+/// fields, so leftover document fields can be collected into `attributes`
+const COLLECTION_ENTRY_FIELDS: [&str; 5] = ["name", "parent", "className", "title", "synonyms"];
+
+fn attribute_value_from_bson(bson: Bson) -> Result<AttributeValue> {
+    Ok(match bson {
+        Bson::Document(doc) => AttributeValue::Map(
+            doc.into_iter()
+                .map(|(k, v)| attribute_value_from_bson(v).map(|v| (k, v)))
+                .collect::<Result<HashMap<String, AttributeValue>>>()?,
+        ),
+        Bson::Array(values) => AttributeValue::List(
+            values
+                .into_iter()
+                .map(attribute_value_from_bson)
+                .collect::<Result<Vec<AttributeValue>>>()?,
+        ),
+        Bson::FloatingPoint(n) => AttributeValue::Number(n),
+        Bson::I32(n) => AttributeValue::Number(f64::from(n)),
+        Bson::I64(n) => AttributeValue::Number(n as f64),
+        Bson::String(s) => AttributeValue::String(s),
+        Bson::Boolean(b) => AttributeValue::Bool(b),
+        Bson::UtcDatetime(dt) => AttributeValue::Date(dt.to_rfc3339()),
+        Bson::Null => AttributeValue::Null,
+        other => Err(format_err!("Unsupported attribute value {:?}", other))?,
+    })
+}
+
+/// Collects the document fields not already modeled by `CollectionEntry`
+/// into `attributes`, skipping MongoDB's own `_id`
+fn attributes_from_document(item: &Document) -> Result<HashMap<String, AttributeValue>> {
+    item.iter()
+        .filter(|(key, _)| !COLLECTION_ENTRY_FIELDS.contains(&key.as_str()))
+        .filter(|(key, _)| key.as_str() != "_id")
+        .map(|(key, value)| attribute_value_from_bson(value.clone()).map(|value| (key.clone(), value)))
+        .collect()
+}
+
 /// A taxonomy entry
 #[derive(Clone, Debug)]
 pub struct CollectionEntry {
@@ -45,6 +113,8 @@ pub struct CollectionEntry {
     pub synonyms: Vec<String>,
     /// Title for this taxonomy entry
     pub title: Title,
+    /// Open, per-entry attributes not modeled by the fields above
+    pub attributes: HashMap<String, AttributeValue>,
 }
 
 impl Into<Document> for CollectionEntry {
@@ -66,13 +136,45 @@ impl Into<Document> for CollectionEntry {
                 .map(|s| Bson::String(s))
                 .collect::<Vec<Bson>>(),
         );
+        for (key, value) in self.attributes {
+            // An attribute named e.g. "name" or "className" would otherwise
+            // silently overwrite the first-class field inserted above
+            if COLLECTION_ENTRY_FIELDS.contains(&key.as_str()) {
+                warn!(
+                    "Attribute '{}' on entry '{}' collides with a reserved field name and was dropped",
+                    key, d.get_str("name").unwrap_or("?")
+                );
+                continue;
+            }
+            d.insert(key, Bson::from(value));
+        }
         d
     }
 }
 
+impl From<AttributeValue> for Bson {
+    fn from(value: AttributeValue) -> Bson {
+        match value {
+            AttributeValue::Map(m) => {
+                Bson::Document(m.into_iter().map(|(k, v)| (k, Bson::from(v))).collect())
+            }
+            AttributeValue::List(l) => Bson::Array(l.into_iter().map(Bson::from).collect()),
+            AttributeValue::Bool(b) => Bson::Boolean(b),
+            AttributeValue::Number(n) => Bson::FloatingPoint(n),
+            AttributeValue::String(s) => Bson::String(s),
+            AttributeValue::Date(s) => match DateTime::parse_from_rfc3339(&s) {
+                Ok(dt) => Bson::UtcDatetime(dt.with_timezone(&Utc)),
+                // Not a well-formed RFC3339 date (shouldn't happen for a `Date` we produced
+                // ourselves); fall back to a plain string rather than losing the value
+                Err(_) => Bson::String(s),
+            },
+            AttributeValue::Null => Bson::Null,
+        }
+    }
+}
+
 /// A map entry
 #[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(deny_unknown_fields)]
 pub struct MapEntry {
     /// Class of this taxonomy entry
     pub class_name: String,
@@ -80,6 +182,10 @@ pub struct MapEntry {
     pub synonyms: Vec<String>,
     /// Human-readable title for this taxonomy entry
     pub title: Title,
+    /// Open, per-entry attributes; catches whatever `deny_unknown_fields`
+    /// used to reject instead of dropping it
+    #[serde(flatten)]
+    pub attributes: HashMap<String, AttributeValue>,
 }
 
 /// A kebab-case identifier that cannot contain a slash
@@ -113,8 +219,135 @@ impl FromStr for Identifier {
     }
 }
 
+impl Identifier {
+    /// Deterministically encodes arbitrary input (spaces, camelCase,
+    /// punctuation) into a valid kebab-case `Identifier`, instead of
+    /// rejecting it the way `from_str` does.
+    ///
+    /// Whitespace, underscores, other ASCII punctuation, and camelCase
+    /// boundaries become hyphens via `heck` (the punctuation itself is
+    /// dropped and cannot be recovered); the reserved `/` and every
+    /// non-ASCII character are instead escaped byte by byte, each byte as
+    /// its own `-xHH-` segment (`HH` being the byte's hex value), so that
+    /// escaping can be undone by `decode`. A kebab-cased segment that would itself be mistaken for one
+    /// of these escapes (e.g. a literal `x86` segment) gets an extra leading
+    /// `x` prepended, per `disambiguate_literal_segment`, so `decode` can
+    /// always tell the two apart.
+    pub fn sanitized(s: &str) -> Identifier {
+        let mut segments: Vec<String> = Vec::new();
+        let mut safe_run = String::new();
+        for c in s.chars() {
+            if c == '/' || !c.is_ascii() {
+                for segment in safe_run.to_kebab_case().split('-') {
+                    if !segment.is_empty() {
+                        segments.push(disambiguate_literal_segment(segment));
+                    }
+                }
+                safe_run.clear();
+                for byte in c.to_string().as_bytes() {
+                    segments.push(format!("x{:02x}", byte));
+                }
+            } else {
+                safe_run.push(c);
+            }
+        }
+        for segment in safe_run.to_kebab_case().split('-') {
+            if !segment.is_empty() {
+                segments.push(disambiguate_literal_segment(segment));
+            }
+        }
+        Identifier(if segments.is_empty() {
+            "x00".to_owned()
+        } else {
+            segments.join("-")
+        })
+    }
+
+    /// Undoes the escaping performed by `sanitized`, recovering the original
+    /// bytes where they were escaped. A hyphen in front of an unescaped
+    /// segment comes back as a single space, and a hyphen in front of an
+    /// escaped byte is dropped; whether a hyphen came from whitespace, an
+    /// underscore, punctuation, or a camelCase boundary is not recovered,
+    /// since that step is lossy.
+    ///
+    /// A multi-byte UTF-8 character is escaped by `sanitized` as a run of
+    /// consecutive `xHH` segments, one per byte, so they are buffered here
+    /// and UTF-8-decoded together rather than cast to `char` one byte at a
+    /// time (which would mangle anything outside the ASCII range).
+    pub fn decode(&self) -> String {
+        let mut out = String::new();
+        let mut pending_bytes: Vec<u8> = Vec::new();
+        for part in self.0.split('-') {
+            let leading_xs = part.chars().take_while(|&c| c == 'x').count();
+            if leading_xs == 1 && part.len() == 3 {
+                if let Ok(byte) = u8::from_str_radix(&part[1..], 16) {
+                    pending_bytes.push(byte);
+                    continue;
+                }
+            } else if leading_xs >= 2 && looks_like_escape(&part[leading_xs - 1..]) {
+                // A literal segment that `disambiguate_literal_segment` gave
+                // an extra leading `x` to avoid colliding with a real escape
+                flush_decoded_bytes(&mut out, &mut pending_bytes);
+                if !out.is_empty() {
+                    out.push(' ');
+                }
+                out.push_str(&part[1..]);
+                continue;
+            }
+            flush_decoded_bytes(&mut out, &mut pending_bytes);
+            if !out.is_empty() {
+                out.push(' ');
+            }
+            out.push_str(part);
+        }
+        flush_decoded_bytes(&mut out, &mut pending_bytes);
+        out
+    }
+}
+
+/// Appends a run of bytes escaped by consecutive `xHH` segments to `out`,
+/// UTF-8-decoding them as a unit so a multi-byte character reconstructs
+/// correctly instead of being cast to `char` byte-by-byte
+fn flush_decoded_bytes(out: &mut String, pending_bytes: &mut Vec<u8>) {
+    if pending_bytes.is_empty() {
+        return;
+    }
+    let bytes = std::mem::replace(pending_bytes, Vec::new());
+    match String::from_utf8(bytes) {
+        Ok(s) => out.push_str(&s),
+        // Not well-formed UTF-8 (shouldn't happen for output `sanitized` produced);
+        // fall back to the previous byte-by-byte behavior rather than losing data
+        Err(e) => {
+            for byte in e.into_bytes() {
+                out.push(byte as char);
+            }
+        }
+    }
+}
+
+/// Whether `segment` (a single `-`-delimited piece of a kebab-case
+/// identifier) would be mistaken by `Identifier::decode` for one of
+/// `sanitized`'s `xHH` byte escapes: one or more literal `x`s followed by
+/// exactly two hex digits
+fn looks_like_escape(segment: &str) -> bool {
+    let leading_xs = segment.chars().take_while(|&c| c == 'x').count();
+    leading_xs > 0 && segment[leading_xs..].len() == 2 && segment[leading_xs..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Prepends an extra `x` to `segment` if it would otherwise collide with a
+/// real `xHH` escape, so `Identifier::decode` can tell the two apart: a
+/// single leading `x` is always a real escape, two or more is always a
+/// disambiguated literal
+fn disambiguate_literal_segment(segment: &str) -> String {
+    if looks_like_escape(segment) {
+        format!("x{}", segment)
+    } else {
+        segment.to_owned()
+    }
+}
+
 /// A path of Identifiers
-#[derive(Debug, Display, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Debug, Display, Eq, Hash, PartialEq, Serialize)]
 pub struct Path(String);
 
 impl Path {
@@ -133,6 +366,18 @@ impl Path {
             .nth(1)
             .map(|s| Identifier(s.to_owned()))
     }
+
+    /// Builds a `Path` from a raw `/`-separated string, sanitizing each
+    /// segment with `Identifier::sanitized` instead of rejecting malformed
+    /// segments the way `from_str` does.
+    pub fn sanitized(s: &str) -> Path {
+        s.split(Self::SEPARATOR).map(Identifier::sanitized).collect()
+    }
+
+    /// Whether this path is `ancestor` itself or nested under it
+    fn is_descendant_of(&self, ancestor: &Path) -> bool {
+        self.0 == ancestor.0 || self.0.starts_with(&format!("{}{}", ancestor.0, Self::SEPARATOR))
+    }
 }
 
 impl FromIterator<Identifier> for Path {
@@ -202,11 +447,62 @@ impl FromStr for Title {
     }
 }
 
+/// Restricts which `CollectionEntry`s `Collection::to_map` and
+/// `Collection::to_collection` operate on, analogous to an
+/// OnlyTables/ExceptTables filter
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Only include entries whose `class_name` is one of these, if given
+    pub only_class: Option<Vec<String>>,
+    /// Exclude entries whose `class_name` is one of these
+    pub except_class: Vec<String>,
+    /// Only include entries whose full path descends from (or is) this `Path`, if given
+    pub subtree: Option<Path>,
+}
+
+impl Filter {
+    /// An unrestricted filter that includes every entry
+    pub fn all() -> Filter {
+        Filter::default()
+    }
+
+    /// Whether `entry`, whose absolute path is `full_path`, passes this filter
+    pub fn should_include(&self, entry: &CollectionEntry, full_path: &Path) -> bool {
+        if let Some(ref only_class) = self.only_class {
+            if !only_class.contains(&entry.class_name) {
+                return false;
+            }
+        }
+        if self.except_class.contains(&entry.class_name) {
+            return false;
+        }
+        if let Some(ref subtree) = self.subtree {
+            if !full_path.is_descendant_of(subtree) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
 /// Represents a taxonomy collection loaded from a mongodb collection
 #[derive(Debug)]
-pub struct Collection(HashMap<Identifier, CollectionEntry>);
+pub struct Collection {
+    entries: HashMap<Identifier, CollectionEntry>,
+    /// Maps a lowercased title/synonym word to every entry whose title or
+    /// synonyms contain it, built once so `search` doesn't rescan on every call
+    search_index: HashMap<String, Vec<Identifier>>,
+}
 
 impl Collection {
+    fn new(entries: HashMap<Identifier, CollectionEntry>) -> Collection {
+        let search_index = build_search_index(&entries);
+        Collection {
+            entries,
+            search_index,
+        }
+    }
+
     /// Reads a CollectionEntry collection from a mongodb collection
     pub fn from_collection(collection: &mongodb::coll::Collection) -> Result<Collection> {
         let cursor = collection.find(None, None)?;
@@ -232,18 +528,93 @@ impl Collection {
                                     .map(|s| s.to_owned())
                                     .ok_or_else(|| format_err!("Invalid type"))
                             }).collect::<Result<Vec<String>>>()?,
+                        attributes: attributes_from_document(&item)?,
                     })
                 })
             }).map_results(|x| (x.name.clone(), x))
             .collect::<Result<HashMap<Identifier, CollectionEntry>>>()?;
 
-        Ok(Collection(taxonomies_hash))
+        Ok(Collection::new(taxonomies_hash))
     }
 
-    /// Writes a collection to a mongodb collection
-    pub fn to_collection(&self, collection: &mongodb::coll::Collection) -> Result<()> {
-        collection.delete_many(Document::new(), None)?;
-        collection.insert_many(self.0.values().map(|entry| entry.clone().into()).collect(), None)?;
+    /// Like `from_collection`, but encodes a malformed `name`/`parent` with
+    /// `Identifier::sanitized` instead of failing the whole load
+    pub fn from_collection_sanitized(collection: &mongodb::coll::Collection) -> Result<Collection> {
+        let cursor = collection.find(None, None)?;
+        let taxonomies_hash = cursor
+            .map(|item_result| {
+                item_result.map_err(Error::from).and_then(|item| {
+                    Ok(CollectionEntry {
+                        name: Identifier::sanitized(item.get_str("name")?),
+                        parent: {
+                            if item.is_null("parent") {
+                                None
+                            } else {
+                                Some(Identifier::sanitized(item.get_str("parent")?))
+                            }
+                        },
+                        class_name: item.get_str("className")?.parse()?,
+                        title: item.get_str("title")?.parse()?,
+                        synonyms: item
+                            .get_array("synonyms")?
+                            .into_iter()
+                            .map(|x| {
+                                x.as_str()
+                                    .map(|s| s.to_owned())
+                                    .ok_or_else(|| format_err!("Invalid type"))
+                            }).collect::<Result<Vec<String>>>()?,
+                        attributes: attributes_from_document(&item)?,
+                    })
+                })
+            }).map_results(|x| (x.name.clone(), x))
+            .collect::<Result<HashMap<Identifier, CollectionEntry>>>()?;
+
+        Ok(Collection::new(taxonomies_hash))
+    }
+
+    /// Writes a collection to a mongodb collection, replacing only the
+    /// entries the existing collection has that pass `filter` so a partial
+    /// edit does not delete everything else. `self` is likewise restricted
+    /// to the entries that pass `filter` before being inserted, so an entry
+    /// that strayed outside the filtered scope (e.g. `--subtree`) is left
+    /// out rather than inserted unconditionally.
+    pub fn to_collection(&self, collection: &mongodb::coll::Collection, filter: &Filter) -> Result<()> {
+        let existing = Collection::from_collection(collection)?;
+        let names_to_replace = existing
+            .entries
+            .values()
+            .filter_map(|entry| match existing.full_path(entry) {
+                Ok(full_path) => Some((entry, full_path)),
+                Err(e) => {
+                    // An unrelated entry elsewhere in the tree shouldn't stop us from
+                    // replacing the entries this store is actually scoped to
+                    warn!(
+                        "Could not resolve the path of existing entry '{}', excluding it from the replace scope: {}",
+                        entry.name, e
+                    );
+                    None
+                }
+            }).filter(|(entry, full_path)| filter.should_include(entry, full_path))
+            .map(|(entry, _)| Bson::String(entry.name.to_string()))
+            .collect::<Vec<Bson>>();
+
+        let mut in_filter = Document::new();
+        in_filter.insert("$in", names_to_replace);
+        let mut delete_filter = Document::new();
+        delete_filter.insert("name", in_filter);
+
+        let entries_to_insert = self
+            .entries
+            .values()
+            .map(|entry| (entry, self.full_path_within_scope(entry)))
+            .filter(|(entry, full_path)| filter.should_include(entry, full_path))
+            .map(|(entry, _)| entry.clone().into())
+            .collect::<Vec<Document>>();
+
+        collection.delete_many(delete_filter, None)?;
+        if !entries_to_insert.is_empty() {
+            collection.insert_many(entries_to_insert, None)?;
+        }
         Ok(())
     }
 
@@ -260,7 +631,7 @@ impl Collection {
                 break;
             }
             current = self
-                .0
+                .entries
                 .get(parent_name)
                 .ok_or_else(|| format_err!("Missing '{}'", parent_name))?;
             path.push(&current.name);
@@ -268,26 +639,295 @@ impl Collection {
         Ok(path.into_iter().rev().collect())
     }
 
-    /// Creates an editable version of the taxonomy collection
-    pub fn to_map(&self) -> Result<Map> {
+    /// Like `full_path`, but for a `self` that may only hold a filtered
+    /// slice of the full taxonomy (e.g. a `--subtree`/`--only-class` dump
+    /// round-tripped through `store`): stops at the first ancestor missing
+    /// from `self.entries` instead of erroring, treating the entry as
+    /// rooted there rather than aborting the whole write
+    fn full_path_within_scope(&self, t: &CollectionEntry) -> Path {
+        let mut path = vec![&t.name];
+        let mut current = t;
+        while let Some(ref parent_name) = current.parent {
+            if parent_name == &current.name {
+                warn!(
+                    "Parent loop detected for entry '{}' - assuming None",
+                    t.name
+                );
+                break;
+            }
+            match self.entries.get(parent_name) {
+                Some(parent_entry) => {
+                    current = parent_entry;
+                    path.push(&current.name);
+                }
+                // The ancestor was excluded by the filter this `self` was dumped with
+                None => break,
+            }
+        }
+        path.into_iter().rev().collect()
+    }
+
+    /// Creates an editable version of the taxonomy collection, restricted to
+    /// the entries that pass `filter`
+    pub fn to_map(&self, filter: &Filter) -> Result<Map> {
         let r = self
-            .0
+            .entries
             .values()
-            .map(|x| {
-                self.full_path(x).map(|fp| {
-                    (
-                        fp,
-                        MapEntry {
-                            title: x.title.clone(),
-                            synonyms: x.synonyms.clone(),
-                            class_name: x.class_name.clone(),
-                        },
-                    )
-                })
-            }).collect::<Result<_>>()?;
+            .map(|x| self.full_path(x).map(|fp| (x, fp)))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|(x, fp)| filter.should_include(x, fp))
+            .map(|(x, fp)| {
+                (
+                    fp,
+                    MapEntry {
+                        title: x.title.clone(),
+                        synonyms: x.synonyms.clone(),
+                        class_name: x.class_name.clone(),
+                        attributes: x.attributes.clone(),
+                    },
+                )
+            }).collect();
 
         Ok(Map(r))
     }
+
+    /// Resolves free-text `query` (e.g. a volunteer's typed skill) against the
+    /// collection's titles and synonyms, returning candidate entries ranked by
+    /// how well they match.
+    ///
+    /// Each whitespace/punctuation-delimited word of `query` is matched against
+    /// the index built by `build_search_index`: an exact word match scores
+    /// highest, a prefix match next, and a match within a length-scaled
+    /// Levenshtein distance (0 for words of 4 chars or fewer, 1 for 5-8, 2
+    /// above that) lowest. An entry's score is the sum of the best match
+    /// weight for each distinct query word it matched, so entries that match
+    /// more of the query outrank entries that match one word well.
+    pub fn search(&self, query: &str) -> Vec<(Identifier, f32)> {
+        const EXACT_WEIGHT: f32 = 1.0;
+        const PREFIX_WEIGHT: f32 = 0.6;
+        const FUZZY_WEIGHT: f32 = 0.3;
+
+        let mut query_words = tokenize(query);
+        query_words.sort();
+        query_words.dedup();
+
+        let mut scores: HashMap<&Identifier, f32> = HashMap::new();
+        for query_word in query_words {
+            let max_distance = max_edit_distance(query_word.chars().count());
+            let mut best_per_entry: HashMap<&Identifier, f32> = HashMap::new();
+            for (index_word, ids) in &self.search_index {
+                let weight = if *index_word == query_word {
+                    EXACT_WEIGHT
+                } else if index_word.starts_with(query_word.as_str()) {
+                    PREFIX_WEIGHT
+                } else if levenshtein_distance(&query_word, index_word) <= max_distance {
+                    FUZZY_WEIGHT
+                } else {
+                    continue;
+                };
+                for id in ids {
+                    let best = best_per_entry.entry(id).or_insert(0.0);
+                    if weight > *best {
+                        *best = weight;
+                    }
+                }
+            }
+            for (id, weight) in best_per_entry {
+                *scores.entry(id).or_insert(0.0) += weight;
+            }
+        }
+
+        let mut ranked: Vec<(Identifier, f32)> = scores
+            .into_iter()
+            .map(|(id, score)| (id.clone(), score))
+            .collect();
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        ranked
+    }
+
+    /// Checks the whole collection for integrity problems: parents
+    /// referencing a missing `Identifier`, multi-hop parent cycles,
+    /// duplicate `Title`s under the same parent, and synonyms claimed by
+    /// more than one entry (which would make `search` ambiguous)
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        for entry in self.entries.values() {
+            if let Some(ref parent_name) = entry.parent {
+                if !self.entries.contains_key(parent_name) {
+                    diagnostics.push(Diagnostic {
+                        severity: Severity::Error,
+                        entries: vec![entry.name.clone()],
+                        message: format!(
+                            "'{}' has a parent '{}' that does not exist",
+                            entry.name, parent_name
+                        ),
+                    });
+                }
+            }
+        }
+
+        // Tracks identifiers already attributed to a reported cycle, so an
+        // N-node cycle is reported once instead of once per member
+        let mut reported_in_cycle: HashSet<&Identifier> = HashSet::new();
+        for entry in self.entries.values() {
+            if reported_in_cycle.contains(&entry.name) {
+                continue;
+            }
+            let mut visited = vec![&entry.name];
+            let mut current = entry;
+            while let Some(ref parent_name) = current.parent {
+                if reported_in_cycle.contains(parent_name) {
+                    // This chain leads into a cycle some other entry already reported,
+                    // rather than being part of a distinct one
+                    break;
+                }
+                if let Some(cycle_start) = visited.iter().position(|&name| name == parent_name) {
+                    let cycle_members = &visited[cycle_start..];
+                    reported_in_cycle.extend(cycle_members.iter().cloned());
+                    diagnostics.push(Diagnostic {
+                        severity: Severity::Error,
+                        entries: cycle_members.iter().cloned().cloned().collect(),
+                        message: format!("Parent cycle detected starting at '{}'", cycle_members[0]),
+                    });
+                    break;
+                }
+                match self.entries.get(parent_name) {
+                    Some(parent_entry) => {
+                        visited.push(&parent_entry.name);
+                        current = parent_entry;
+                    }
+                    // Already reported above as a missing parent
+                    None => break,
+                }
+            }
+        }
+
+        let mut names_by_title_by_parent: HashMap<Option<&Identifier>, HashMap<&str, Vec<&Identifier>>> =
+            HashMap::new();
+        for entry in self.entries.values() {
+            names_by_title_by_parent
+                .entry(entry.parent.as_ref())
+                .or_insert_with(HashMap::new)
+                .entry(entry.title.0.as_str())
+                .or_insert_with(Vec::new)
+                .push(&entry.name);
+        }
+        for names_by_title in names_by_title_by_parent.values() {
+            for (title, names) in names_by_title {
+                if names.len() > 1 {
+                    diagnostics.push(Diagnostic {
+                        severity: Severity::Warning,
+                        entries: names.iter().map(|&name| name.clone()).collect(),
+                        message: format!(
+                            "Title {:?} is used by more than one entry under the same parent",
+                            title
+                        ),
+                    });
+                }
+            }
+        }
+
+        let mut names_by_synonym: HashMap<&str, Vec<&Identifier>> = HashMap::new();
+        for entry in self.entries.values() {
+            for synonym in &entry.synonyms {
+                names_by_synonym
+                    .entry(synonym.as_str())
+                    .or_insert_with(Vec::new)
+                    .push(&entry.name);
+            }
+        }
+        for (synonym, names) in names_by_synonym {
+            if names.len() > 1 {
+                diagnostics.push(Diagnostic {
+                    severity: Severity::Warning,
+                    entries: names.into_iter().cloned().collect(),
+                    message: format!("Synonym {:?} is claimed by more than one entry", synonym),
+                });
+            }
+        }
+
+        diagnostics
+    }
+}
+
+/// How serious a `Diagnostic` from `Collection::validate` is
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    /// A structural problem that would break lookups like `full_path`
+    Error,
+    /// A data-quality problem that degrades matching but doesn't break lookups
+    Warning,
+}
+
+/// A single integrity problem found by `Collection::validate`
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// How serious this problem is
+    pub severity: Severity,
+    /// The entries this diagnostic is about
+    pub entries: Vec<Identifier>,
+    /// A human-readable description of the problem
+    pub message: String,
+}
+
+/// Splits `s` into lowercased word tokens on anything that isn't alphanumeric
+fn tokenize(s: &str) -> Vec<String> {
+    s.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_owned())
+        .collect()
+}
+
+/// The maximum Levenshtein distance a fuzzy match may have, scaled by word length
+fn max_edit_distance(word_len: usize) -> usize {
+    match word_len {
+        0..=4 => 0,
+        5..=8 => 1,
+        _ => 2,
+    }
+}
+
+/// Computes the Levenshtein edit distance between `a` and `b`
+fn levenshtein_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut previous_row: Vec<usize> = (0..=b.len()).collect();
+    let mut current_row = vec![0; b.len() + 1];
+
+    for (i, a_char) in a.iter().enumerate() {
+        current_row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution_cost = if a_char == b_char { 0 } else { 1 };
+            current_row[j + 1] = (previous_row[j + 1] + 1)
+                .min(current_row[j] + 1)
+                .min(previous_row[j] + substitution_cost);
+        }
+        std::mem::swap(&mut previous_row, &mut current_row);
+    }
+
+    previous_row[b.len()]
+}
+
+/// Builds an inverted index from lowercased title/synonym word to the
+/// `Identifier`s of entries containing that word
+fn build_search_index(entries: &HashMap<Identifier, CollectionEntry>) -> HashMap<String, Vec<Identifier>> {
+    let mut index: HashMap<String, Vec<Identifier>> = HashMap::new();
+    for entry in entries.values() {
+        let mut words = tokenize(&entry.title.0);
+        for synonym in &entry.synonyms {
+            words.extend(tokenize(synonym));
+        }
+        words.sort();
+        words.dedup();
+        for word in words {
+            index.entry(word).or_insert_with(Vec::new).push(entry.name.clone());
+        }
+    }
+    index
 }
 
 /// Represents an editable form of a taxonomy collection
@@ -296,7 +936,7 @@ pub struct Map(HashMap<Path, MapEntry>);
 
 impl Map {
     pub fn into_collection(self) -> Result<Collection> {
-        Ok(Collection(
+        Ok(Collection::new(
             self.0
                 .into_iter()
                 .map(|(path, entry)| {
@@ -311,9 +951,516 @@ impl Map {
                             parent: path.parent(),
                             synonyms: entry.synonyms,
                             title: entry.title,
+                            attributes: entry.attributes,
                         },
                     ))
                 }).collect::<Result<_>>()?,
         ))
     }
 }
+
+/// An editable form of a taxonomy collection whose paths have not yet been
+/// validated, for loading source data (e.g. authored by non-engineers) whose
+/// paths may not already be kebab-case
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RawMap(HashMap<String, MapEntry>);
+
+impl RawMap {
+    /// Like `Map::into_collection`, but sanitizes each path with
+    /// `Path::sanitized` instead of rejecting malformed rows
+    pub fn into_collection_sanitized(self) -> Collection {
+        Collection::new(
+            self.0
+                .into_iter()
+                .map(|(raw_path, entry)| {
+                    let path = Path::sanitized(&raw_path);
+                    let path_name = path.name().unwrap_or_else(|| Identifier::sanitized(""));
+                    (
+                        path_name.clone(),
+                        CollectionEntry {
+                            class_name: entry.class_name,
+                            name: path_name,
+                            parent: path.parent(),
+                            synonyms: entry.synonyms,
+                            title: entry.title,
+                            attributes: entry.attributes,
+                        },
+                    )
+                }).collect(),
+        )
+    }
+}
+
+/// A 1-based line/column location within a source document
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Location {
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column number
+    pub column: usize,
+}
+
+impl Location {
+    fn from_offset(source: &str, offset: usize) -> Location {
+        let mut line = 1;
+        let mut column = 1;
+        for c in source[..offset.min(source.len())].chars() {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        Location { line, column }
+    }
+}
+
+/// A `Map` deserialization failure located within the original source
+/// document, instead of the opaque message a bare serde error gives
+#[derive(Debug)]
+pub struct LocatedError {
+    /// Where the problem was found, if a location could be determined
+    pub location: Option<Location>,
+    /// What was wrong
+    pub message: String,
+}
+
+impl fmt::Display for LocatedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Some(location) => write!(f, "{}:{}: {}", location.line, location.column, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for LocatedError {}
+
+/// A `MapEntry` whose `title` has not yet been validated as a `Title`, so a
+/// failure can be reported with a source location instead of aborting
+/// deserialization outright
+#[derive(Debug, Deserialize)]
+struct TextMapEntry {
+    class_name: String,
+    synonyms: Vec<String>,
+    title: String,
+    #[serde(flatten)]
+    attributes: HashMap<String, AttributeValue>,
+}
+
+/// Parses TOML-encoded `source` into a `Map`, returning a `LocatedError`
+/// pointing at the offending `Identifier`/`Title` instead of a generic
+/// serde message when one fails validation
+pub fn map_from_toml_located(source: &str) -> std::result::Result<Map, LocatedError> {
+    let raw: HashMap<String, TextMapEntry> = toml::from_str(source).map_err(|e| LocatedError {
+        location: e
+            .line_col()
+            .map(|(line, column)| Location { line: line + 1, column: column + 1 }),
+        message: e.to_string(),
+    })?;
+    map_from_text_located(source, raw)
+}
+
+/// Parses JSON-encoded `source` into a `Map`, returning a `LocatedError`
+/// pointing at the offending `Identifier`/`Title` instead of a generic
+/// serde message when one fails validation
+pub fn map_from_json_located(source: &str) -> std::result::Result<Map, LocatedError> {
+    let raw: HashMap<String, TextMapEntry> = serde_json::from_str(source).map_err(|e| LocatedError {
+        location: Some(Location {
+            line: e.line(),
+            column: e.column(),
+        }),
+        message: e.to_string(),
+    })?;
+    map_from_text_located(source, raw)
+}
+
+/// Validates each path/title of a raw (not yet `Identifier`/`Title`
+/// validated) map read from `source`, locating any failure by searching
+/// `source` for the offending literal starting from that entry's own
+/// table/object (found via `find_entry_start`), rather than the whole
+/// document, so a literal shared with an earlier entry isn't mistaken for
+/// this one. This is still best-effort, short of a genuinely spanned parser:
+/// a literal that occurs verbatim more than once from that point on is
+/// located at its first such occurrence.
+fn map_from_text_located(
+    source: &str,
+    raw: HashMap<String, TextMapEntry>,
+) -> std::result::Result<Map, LocatedError> {
+    let mut entries = HashMap::with_capacity(raw.len());
+    for (raw_path, raw_entry) in raw {
+        let entry_start = find_entry_start(source, &raw_path).unwrap_or(0);
+        let path = Path::from_str(&raw_path).map_err(|e| LocatedError {
+            location: locate(source, entry_start, &raw_path),
+            message: e.to_string(),
+        })?;
+        let title = Title::from_str(&raw_entry.title).map_err(|e| LocatedError {
+            location: locate(source, entry_start, &raw_entry.title),
+            message: e.to_string(),
+        })?;
+        entries.insert(
+            path,
+            MapEntry {
+                class_name: raw_entry.class_name,
+                synonyms: raw_entry.synonyms,
+                title,
+                attributes: raw_entry.attributes,
+            },
+        );
+    }
+    Ok(Map(entries))
+}
+
+/// Finds where `raw_path`'s own TOML table header (`["raw_path"]` or
+/// `[raw_path]`) or JSON object key (`"raw_path":`) begins in `source`, so
+/// `locate` can be scoped to that entry instead of the whole document
+fn find_entry_start(source: &str, raw_path: &str) -> Option<usize> {
+    let quoted = format!("{:?}", raw_path);
+    source
+        .find(&format!("[{}]", quoted))
+        .or_else(|| source.find(&format!("{}:", quoted)))
+        .or_else(|| source.find(&format!("[{}]", raw_path)))
+}
+
+/// Finds the line/column of `literal`'s bare or quoted occurrence in
+/// `source`, searching only from byte offset `from` onward
+fn locate(source: &str, from: usize, literal: &str) -> Option<Location> {
+    let haystack = &source[from..];
+    haystack
+        .find(literal)
+        .or_else(|| haystack.find(&format!("\"{}\"", literal)))
+        .map(|offset| Location::from_offset(source, from + offset))
+}
+
+/// Serializes `map` to the indentation-based outline format: each line is
+/// `identifier: Title`, optionally prefixed with `[class]` and suffixed with
+/// `| synonym, synonym`, and a line nested one level deeper than the line
+/// above it is that entry's child. A class name, title, or synonym
+/// containing one of the format's own delimiters (`\`, `|`, `,`, `]`) is
+/// escaped via `escape_outline_field` so the round trip stays lossless.
+/// Entry attributes are not written, so they do not survive the round trip.
+pub fn map_to_outline(map: &Map) -> String {
+    let mut children_by_parent_path: HashMap<&str, Vec<(&Path, &MapEntry)>> = HashMap::new();
+    for (path, entry) in &map.0 {
+        let parent_path = match path.0.rfind(Path::SEPARATOR) {
+            Some(i) => &path.0[..i],
+            None => "",
+        };
+        children_by_parent_path
+            .entry(parent_path)
+            .or_insert_with(Vec::new)
+            .push((path, entry));
+    }
+
+    let mut output = String::new();
+    write_outline_children(&mut output, "", &children_by_parent_path, 0);
+    output
+}
+
+fn write_outline_children(
+    output: &mut String,
+    parent_path: &str,
+    children_by_parent_path: &HashMap<&str, Vec<(&Path, &MapEntry)>>,
+    depth: usize,
+) {
+    let mut children = match children_by_parent_path.get(parent_path) {
+        Some(children) => children.clone(),
+        None => return,
+    };
+    children.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+
+    for (path, entry) in children {
+        output.push_str(&"  ".repeat(depth));
+        if !entry.class_name.is_empty() {
+            output.push_str(&format!("[{}] ", escape_outline_field(&entry.class_name)));
+        }
+        if let Some(name) = path.name() {
+            output.push_str(&name.to_string());
+        }
+        output.push_str(": ");
+        output.push_str(&escape_outline_field(&entry.title.0));
+        if !entry.synonyms.is_empty() {
+            output.push_str(" | ");
+            output.push_str(
+                &entry
+                    .synonyms
+                    .iter()
+                    .map(|s| escape_outline_field(s))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            );
+        }
+        output.push('\n');
+
+        write_outline_children(output, &path.0, children_by_parent_path, depth + 1);
+    }
+}
+
+/// Escapes `\`, `|`, `,` and `]` with a leading `\` so a class name, title,
+/// or synonym containing one of the outline format's delimiters round-trips
+/// through `parse_outline_content` instead of being silently split
+fn escape_outline_field(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if let '\\' | '|' | ',' | ']' = c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Undoes `escape_outline_field`
+fn unescape_outline_field(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                unescaped.push(escaped);
+                continue;
+            }
+        }
+        unescaped.push(c);
+    }
+    unescaped
+}
+
+/// Finds the first occurrence of `needle` in `s` that isn't `\`-escaped
+fn find_unescaped(s: &str, needle: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == needle {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Splits `s` on occurrences of `sep` that aren't `\`-escaped, leaving escape
+/// sequences intact in each part for `unescape_outline_field` to resolve
+fn split_unescaped(s: &str, sep: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            current.push(c);
+            if let Some(escaped) = chars.next() {
+                current.push(escaped);
+            }
+        } else if c == sep {
+            parts.push(current.clone());
+            current.clear();
+        } else {
+            current.push(c);
+        }
+    }
+    parts.push(current);
+    parts
+}
+
+/// Parses a single outline content string (the part of a line after
+/// indentation) into `(class_name, identifier, title, synonyms)`, undoing
+/// `escape_outline_field` on each
+fn parse_outline_content(content: &str) -> Result<(String, String, String, Vec<String>)> {
+    let (class_name, rest) = if content.starts_with('[') {
+        let end = find_unescaped(&content[1..], ']')
+            .ok_or_else(|| format_err!("Outline line {:?} is missing a closing ']'", content))?;
+        (
+            unescape_outline_field(&content[1..1 + end]),
+            content[1 + end + 1..].trim_start(),
+        )
+    } else {
+        (String::new(), content)
+    };
+
+    let (before_synonyms, synonyms) = match find_unescaped(rest, '|') {
+        Some(i) => {
+            let synonym_text = rest[i + 1..].trim();
+            let synonyms = if synonym_text.is_empty() {
+                Vec::new()
+            } else {
+                split_unescaped(synonym_text, ',')
+                    .iter()
+                    .map(|s| unescape_outline_field(s.trim()))
+                    .collect()
+            };
+            (rest[..i].trim_end(), synonyms)
+        }
+        None => (rest.trim_end(), Vec::new()),
+    };
+
+    let colon = before_synonyms
+        .find(':')
+        .ok_or_else(|| format_err!("Outline line {:?} is missing a ':' between identifier and title", content))?;
+    let identifier = before_synonyms[..colon].trim().to_owned();
+    let title = unescape_outline_field(before_synonyms[colon + 1..].trim());
+
+    Ok((class_name, identifier, title, synonyms))
+}
+
+/// Parses the indentation-based outline format into a `Map`, reconstructing
+/// each entry's `Path` from the indentation stack. Rejects a line indented
+/// more than one level deeper than its parent, and a line whose indentation
+/// isn't a multiple of the width established by the document's first
+/// indented line, with a `LocatedError`.
+pub fn map_from_outline_located(source: &str) -> std::result::Result<Map, LocatedError> {
+    let mut entries = HashMap::new();
+    let mut stack: Vec<(usize, Identifier)> = Vec::new();
+    let mut indent_unit: Option<usize> = None;
+
+    for (line_number, raw_line) in source.lines().enumerate() {
+        if raw_line.trim().is_empty() {
+            continue;
+        }
+        let content = raw_line.trim();
+        let indent = raw_line.len() - raw_line.trim_start().len();
+        let location = Location {
+            line: line_number + 1,
+            column: indent + 1,
+        };
+
+        let depth = if indent == 0 {
+            0
+        } else {
+            let unit = *indent_unit.get_or_insert(indent);
+            if indent % unit != 0 {
+                return Err(LocatedError {
+                    location: Some(location),
+                    message: format!(
+                        "Indentation of {} spaces is not a multiple of the document's {}-space indent width",
+                        indent, unit
+                    ),
+                });
+            }
+            indent / unit
+        };
+
+        if depth > stack.len() {
+            return Err(LocatedError {
+                location: Some(location),
+                message: "Line is indented more than one level deeper than its parent".to_owned(),
+            });
+        }
+        stack.truncate(depth);
+
+        let (class_name, raw_identifier, raw_title, synonyms) =
+            parse_outline_content(content).map_err(|e| LocatedError {
+                location: Some(location),
+                message: e.to_string(),
+            })?;
+
+        let identifier = Identifier::from_str(&raw_identifier).map_err(|e| LocatedError {
+            location: Some(location),
+            message: e.to_string(),
+        })?;
+
+        let title = Title::from_str(&raw_title).map_err(|e| LocatedError {
+            location: Some(location),
+            message: e.to_string(),
+        })?;
+
+        let path: Path = stack
+            .iter()
+            .map(|(_, id)| id.clone())
+            .chain(std::iter::once(identifier.clone()))
+            .collect();
+
+        entries.insert(
+            path,
+            MapEntry {
+                class_name,
+                synonyms,
+                title,
+                attributes: HashMap::new(),
+            },
+        );
+
+        stack.push((indent, identifier));
+    }
+
+    Ok(Map(entries))
+}
+
+/// Like `map_from_outline_located`, but encodes a malformed identifier with
+/// `Identifier::sanitized` instead of failing the whole parse
+pub fn map_from_outline_sanitized(source: &str) -> std::result::Result<Map, LocatedError> {
+    let mut entries = HashMap::new();
+    let mut stack: Vec<(usize, Identifier)> = Vec::new();
+    let mut indent_unit: Option<usize> = None;
+
+    for (line_number, raw_line) in source.lines().enumerate() {
+        if raw_line.trim().is_empty() {
+            continue;
+        }
+        let content = raw_line.trim();
+        let indent = raw_line.len() - raw_line.trim_start().len();
+        let location = Location {
+            line: line_number + 1,
+            column: indent + 1,
+        };
+
+        let depth = if indent == 0 {
+            0
+        } else {
+            let unit = *indent_unit.get_or_insert(indent);
+            if indent % unit != 0 {
+                return Err(LocatedError {
+                    location: Some(location),
+                    message: format!(
+                        "Indentation of {} spaces is not a multiple of the document's {}-space indent width",
+                        indent, unit
+                    ),
+                });
+            }
+            indent / unit
+        };
+
+        if depth > stack.len() {
+            return Err(LocatedError {
+                location: Some(location),
+                message: "Line is indented more than one level deeper than its parent".to_owned(),
+            });
+        }
+        stack.truncate(depth);
+
+        let (class_name, raw_identifier, raw_title, synonyms) =
+            parse_outline_content(content).map_err(|e| LocatedError {
+                location: Some(location),
+                message: e.to_string(),
+            })?;
+
+        let identifier = Identifier::sanitized(&raw_identifier);
+
+        let title = Title::from_str(&raw_title).map_err(|e| LocatedError {
+            location: Some(location),
+            message: e.to_string(),
+        })?;
+
+        let path: Path = stack
+            .iter()
+            .map(|(_, id)| id.clone())
+            .chain(std::iter::once(identifier.clone()))
+            .collect();
+
+        entries.insert(
+            path,
+            MapEntry {
+                class_name,
+                synonyms,
+                title,
+                attributes: HashMap::new(),
+            },
+        );
+
+        stack.push((indent, identifier));
+    }
+
+    Ok(Map(entries))
+}
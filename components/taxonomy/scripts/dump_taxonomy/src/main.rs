@@ -18,11 +18,36 @@ enum Command {
     Load {
         #[structopt(subcommand)]
         format: Format,
+
+        #[structopt(flatten)]
+        filter: FilterArgs,
+
+        #[structopt(
+            help = "encode malformed names/parents with Identifier::sanitized instead of failing the load",
+            long = "sanitize"
+        )]
+        sanitize: bool,
     },
     #[structopt(name = "store")]
     Store {
         #[structopt(subcommand)]
         format: Format,
+
+        #[structopt(flatten)]
+        filter: FilterArgs,
+
+        #[structopt(
+            help = "encode malformed paths with Identifier::sanitized instead of failing the store",
+            long = "sanitize"
+        )]
+        sanitize: bool,
+    },
+    #[structopt(name = "validate")]
+    Validate,
+    #[structopt(name = "search")]
+    Search {
+        #[structopt(help = "free-text query to match against titles and synonyms")]
+        query: String,
     },
 }
 
@@ -32,6 +57,47 @@ enum Format {
     Json,
     #[structopt(name = "toml")]
     Toml,
+    #[structopt(name = "outline")]
+    Outline,
+}
+
+#[derive(Debug, StructOpt)]
+struct FilterArgs {
+    #[structopt(
+        help = "only include entries with this class (repeatable)",
+        long = "only-class"
+    )]
+    only_class: Vec<String>,
+
+    #[structopt(
+        help = "exclude entries with this class (repeatable)",
+        long = "except-class"
+    )]
+    except_class: Vec<String>,
+
+    #[structopt(
+        help = "only include entries nested under (or at) this path",
+        long = "subtree"
+    )]
+    subtree: Option<String>,
+}
+
+impl FilterArgs {
+    fn into_filter(self) -> Result<taxonomy::Filter, Error> {
+        Ok(taxonomy::Filter {
+            only_class: if self.only_class.is_empty() {
+                None
+            } else {
+                Some(self.only_class)
+            },
+            except_class: self.except_class,
+            subtree: self
+                .subtree
+                .map(|s| s.parse())
+                .transpose()
+                .context("Failed to parse --subtree")?,
+        })
+    }
 }
 
 #[derive(Debug, StructOpt)]
@@ -109,39 +175,100 @@ main!(|args: Cli, log_level: verbosity| {
     };
 
     match args.command {
-        Command::Load{format} => {
+        Command::Load{format, filter, sanitize} => {
+            let filter = filter.into_filter()?;
 
             // Extract the data from the collection to memory
-            let taxonomy_collection = taxonomy::Collection::from_collection(&collection)
-                .context("Failed to extract collection from database")?;
+            let taxonomy_collection = if sanitize {
+                taxonomy::Collection::from_collection_sanitized(&collection)
+            } else {
+                taxonomy::Collection::from_collection(&collection)
+            }.context("Failed to extract collection from database")?;
 
             // Convert the data to an ediable form
             let taxonomy_collection_editable = taxonomy_collection
-                .to_map()
+                .to_map(&filter)
                 .context("Failed to serialize taxonomy collection to stdout")?;
 
             // Convert it to a string
             let taxonomy_string = match format {
                 Format::Toml => toml::ser::to_string(&taxonomy_collection_editable).map_err(Error::from),
                 Format::Json => serde_json::to_string_pretty(&taxonomy_collection_editable).map_err(Error::from),
+                Format::Outline => Ok(taxonomy::map_to_outline(&taxonomy_collection_editable)),
             }.context("Failed to convert taxonomy collection to TOML")?;
 
             println!("{}", taxonomy_string);
         }
-        Command::Store{format} => {
+        Command::Store{format, filter, sanitize} => {
             use std::io::Read;
 
+            let filter = filter.into_filter()?;
+
             let mut s = String::new();
             let stdin = std::io::stdin();
             stdin.lock().read_to_string(&mut s)?;
 
-            let taxonomy_map: taxonomy::Map = match format {
-                Format::Toml => toml::de::from_str(&s).map_err(Error::from),
-                Format::Json => serde_json::from_str(&s).map_err(Error::from),
-            }.context("Failed to deserialize taxonomy map from stdin")?;
+            let taxonomy_collection = if sanitize {
+                match format {
+                    Format::Toml => {
+                        let raw: taxonomy::RawMap = toml::from_str(&s).map_err(Error::from)
+                            .context("Failed to deserialize taxonomy map from stdin")?;
+                        raw.into_collection_sanitized()
+                    }
+                    Format::Json => {
+                        let raw: taxonomy::RawMap = serde_json::from_str(&s).map_err(Error::from)
+                            .context("Failed to deserialize taxonomy map from stdin")?;
+                        raw.into_collection_sanitized()
+                    }
+                    Format::Outline => {
+                        let taxonomy_map = taxonomy::map_from_outline_sanitized(&s)
+                            .map_err(|located_error| format_err!("<stdin>:{}", located_error))
+                            .context("Failed to deserialize taxonomy map from stdin")?;
+                        taxonomy_map.into_collection().context("Failed to pack taxonomy map into collection format")?
+                    }
+                }
+            } else {
+                let taxonomy_map: taxonomy::Map = match format {
+                    Format::Toml => taxonomy::map_from_toml_located(&s),
+                    Format::Json => taxonomy::map_from_json_located(&s),
+                    Format::Outline => taxonomy::map_from_outline_located(&s),
+                }.map_err(|located_error| format_err!("<stdin>:{}", located_error))
+                .context("Failed to deserialize taxonomy map from stdin")?;
+
+                taxonomy_map.into_collection().context("Failed to pack taxonomy map into collection format")?
+            };
+
+            taxonomy_collection.to_collection(&collection, &filter).context("Failed to write taxonomy collection to database")?;
+        },
+        Command::Validate => {
+            let taxonomy_collection = taxonomy::Collection::from_collection(&collection)
+                .context("Failed to extract collection from database")?;
+
+            let diagnostics = taxonomy_collection.validate();
+            let mut saw_error = false;
+            for diagnostic in &diagnostics {
+                match diagnostic.severity {
+                    taxonomy::Severity::Error => {
+                        saw_error = true;
+                        eprintln!("error: {}", diagnostic.message);
+                    }
+                    taxonomy::Severity::Warning => eprintln!("warning: {}", diagnostic.message),
+                }
+            }
+            if diagnostics.is_empty() {
+                println!("No integrity problems found");
+            }
+            if saw_error {
+                std::process::exit(1);
+            }
+        },
+        Command::Search{query} => {
+            let taxonomy_collection = taxonomy::Collection::from_collection(&collection)
+                .context("Failed to extract collection from database")?;
 
-            let taxonomy_collection = taxonomy_map.into_collection().context("Failed to pack taxonomy map into collection format")?;
-            // taxonomy_collection.to_collection(&collection)?;
+            for (id, score) in taxonomy_collection.search(&query) {
+                println!("{:.3}\t{}", score, id);
+            }
         },
     }
 });